@@ -42,3 +42,40 @@ impl DownloadRecord {
         }
     }
 }
+
+/// tracks how far a live/DVR recording has progressed, so a restart can
+/// resume instead of re-downloading everything already on disk
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LiveRecord {
+    pub highest_sequence: u64,
+}
+
+impl LiveRecord {
+    pub fn load<P>(dir: P) -> anyhow::Result<Self>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        let path = dir.as_ref().join("live_record.json");
+        let file = std::fs::File::open(path)?;
+
+        let record = serde_json::from_reader(file)?;
+
+        Ok(record)
+    }
+
+    pub fn save<P>(&self, dir: P) -> anyhow::Result<()>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        let path = dir.as_ref().join("live_record.json");
+        let file = std::fs::File::create(path)?;
+
+        serde_json::to_writer_pretty(file, self)?;
+
+        Ok(())
+    }
+
+    pub fn new(highest_sequence: u64) -> Self {
+        Self { highest_sequence }
+    }
+}
@@ -2,7 +2,9 @@ use std::fs;
 use std::io::Write;
 use std::sync::Arc;
 
+use aes::cipher::{BlockDecryptMut, KeyIvInit};
 use bytes::Bytes;
+use cbc::cipher::block_padding::Pkcs7;
 use indicatif::{ProgressBar, ProgressStyle};
 use m3u8_rs::{MediaPlaylist, Playlist};
 use md5::{Digest, Md5};
@@ -15,6 +17,35 @@ mod video;
 
 pub use video::VideoUtil;
 
+type Aes128CbcDec = cbc::Decryptor<aes::Aes128>;
+
+fn decrypt_aes128_cbc(key: &[u8; 16], iv: &[u8; 16], data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    Aes128CbcDec::new(key.into(), iv.into())
+        .decrypt_padded_vec_mut::<Pkcs7>(data)
+        .map_err(|e| anyhow::anyhow!("AES-128 decrypt failed: {}", e))
+}
+
+/// the default IV when `EXT-X-KEY` has no explicit `IV` attribute: the
+/// segment's absolute media sequence number, big-endian, left-padded with
+/// zeroes to 16 bytes
+fn iv_from_sequence(seq: u64) -> [u8; 16] {
+    let mut iv = [0u8; 16];
+    iv[8..].copy_from_slice(&seq.to_be_bytes());
+    iv
+}
+
+fn parse_iv(raw: &str) -> Option<[u8; 16]> {
+    let hex = raw.trim_start_matches("0x").trim_start_matches("0X");
+    if hex.len() != 32 {
+        return None;
+    }
+    let mut iv = [0u8; 16];
+    for (i, byte) in iv.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(iv)
+}
+
 fn basename(src: &str) -> &str {
     std::path::Path::new(src)
         .file_name()
@@ -23,6 +54,61 @@ fn basename(src: &str) -> &str {
         .unwrap()
 }
 
+#[cfg(test)]
+mod aes_tests {
+    use super::*;
+    use aes::cipher::BlockEncryptMut;
+
+    type Aes128CbcEnc = cbc::Encryptor<aes::Aes128>;
+
+    #[test]
+    fn parse_iv_accepts_hex_with_or_without_0x_prefix() {
+        let with_prefix = parse_iv("0x000102030405060708090A0B0C0D0E0F").unwrap();
+        let without_prefix = parse_iv("000102030405060708090a0b0c0d0e0f").unwrap();
+        assert_eq!(with_prefix, without_prefix);
+        assert_eq!(with_prefix[0], 0x00);
+        assert_eq!(with_prefix[15], 0x0f);
+    }
+
+    #[test]
+    fn parse_iv_rejects_wrong_length_or_non_hex() {
+        assert!(parse_iv("0x0001").is_none());
+        assert!(parse_iv("0x000102030405060708090a0b0c0d0e0g").is_none());
+    }
+
+    #[test]
+    fn iv_from_sequence_is_big_endian_in_the_last_eight_bytes() {
+        assert_eq!(iv_from_sequence(0), [0u8; 16]);
+
+        let iv = iv_from_sequence(0x0102030405060708);
+        assert_eq!(&iv[..8], &[0u8; 8]);
+        assert_eq!(&iv[8..], &0x0102030405060708u64.to_be_bytes());
+    }
+
+    #[test]
+    fn decrypt_aes128_cbc_roundtrips_with_the_matching_encryptor() {
+        let key = [0x42u8; 16];
+        let iv = [0x24u8; 16];
+        let plaintext = b"hello m3u8 segment".to_vec();
+
+        let ciphertext = Aes128CbcEnc::new(&key.into(), &iv.into())
+            .encrypt_padded_vec_mut::<Pkcs7>(&plaintext);
+
+        let decrypted = decrypt_aes128_cbc(&key, &iv, &ciphertext).unwrap();
+        assert_eq!(decrypted, plaintext);
+    }
+
+    #[test]
+    fn decrypt_aes128_cbc_rejects_the_wrong_key() {
+        let iv = [0x24u8; 16];
+        let plaintext = b"hello m3u8 segment".to_vec();
+        let ciphertext = Aes128CbcEnc::new(&[0x42u8; 16].into(), &iv.into())
+            .encrypt_padded_vec_mut::<Pkcs7>(&plaintext);
+
+        assert!(decrypt_aes128_cbc(&[0x43u8; 16], &iv, &ciphertext).is_err());
+    }
+}
+
 fn clean_dir<P>(path: P) -> anyhow::Result<()>
 where
     P: AsRef<std::path::Path>,
@@ -48,7 +134,101 @@ where
     Ok(())
 }
 
-#[derive(Debug)]
+/// how to pick a stream out of a master playlist's variants
+#[derive(Debug, Clone)]
+pub enum VariantSelector {
+    /// the variant with the greatest resolution/bandwidth (the previous,
+    /// unconditional behavior)
+    Highest,
+    /// the variant with the least resolution/bandwidth
+    Lowest,
+    /// the highest-bandwidth variant at or under `max` bits/sec
+    ByBandwidth(u64),
+    /// the highest-resolution variant that fits within `width`x`height`
+    ByResolution(u64, u64),
+}
+
+impl Default for VariantSelector {
+    fn default() -> Self {
+        VariantSelector::Highest
+    }
+}
+
+/// progress notifications emitted while downloading, so a library consumer
+/// can drive its own UI instead of the bundled `indicatif` progress bar
+#[derive(Debug, Clone)]
+pub enum DownloadEvent {
+    /// the playlist was resolved and `segments` segments are about to be
+    /// downloaded
+    PlaylistResolved { segments: usize },
+    /// the (or an) encryption key was downloaded
+    KeyDownloaded,
+    /// one segment finished downloading
+    SegmentDownloaded {
+        index: usize,
+        total: usize,
+        uri: String,
+    },
+    /// every segment of the current set downloaded (the whole playlist, or
+    /// one live poll's new segments); emitted before the index is written
+    Finished,
+    /// a segment failed (its fetch after exhausting retries, or its
+    /// decryption or write); the download is aborting
+    Failed { uri: String, error: String },
+}
+
+type ProgressCallback = dyn Fn(DownloadEvent) + Send + Sync;
+
+/// drives the bundled `indicatif` progress bar from `DownloadEvent`s; used
+/// when the caller doesn't supply their own `on_progress` callback
+struct DefaultProgressReporter {
+    bar: std::sync::Mutex<Option<ProgressBar>>,
+}
+
+impl DefaultProgressReporter {
+    fn new() -> Self {
+        Self {
+            bar: std::sync::Mutex::new(None),
+        }
+    }
+
+    fn handle(&self, event: DownloadEvent) {
+        match event {
+            DownloadEvent::PlaylistResolved { segments } => {
+                let pb = ProgressBar::new(segments as u64);
+                pb.set_style(
+                    ProgressStyle::with_template(
+                        "{spinner:.green} [{wide_bar:.cyan/blue}] {pos}/{len} ({eta})",
+                    )
+                    .unwrap()
+                    .progress_chars("#>-"),
+                );
+                *self.bar.lock().unwrap() = Some(pb);
+            }
+            DownloadEvent::KeyDownloaded => {
+                tracing::info!("key downloaded");
+            }
+            DownloadEvent::SegmentDownloaded { .. } => {
+                if let Some(pb) = self.bar.lock().unwrap().as_ref() {
+                    pb.inc(1);
+                }
+            }
+            DownloadEvent::Finished => {
+                if let Some(pb) = self.bar.lock().unwrap().take() {
+                    pb.finish_with_message("downloaded");
+                }
+                tracing::info!("segments downloaded");
+            }
+            DownloadEvent::Failed { uri, error } => {
+                if let Some(pb) = self.bar.lock().unwrap().take() {
+                    pb.abandon();
+                }
+                tracing::error!("segment {} failed: {}", uri, error);
+            }
+        }
+    }
+}
+
 struct DownloaderBuilderPart {
     target: url::Url,
     save_dir: Option<std::path::PathBuf>,
@@ -58,9 +238,18 @@ struct DownloaderBuilderPart {
     client: Option<reqwest::Client>,
 
     max_download_concurrency: usize,
+    max_retries: usize,
+
+    live: bool,
+    max_duration: Option<std::time::Duration>,
+
+    decrypt: bool,
+
+    variant_selector: VariantSelector,
+
+    on_progress: Option<Arc<ProgressCallback>>,
 }
 
-#[derive(Debug)]
 pub struct DownloaderBuilder {
     inner: anyhow::Result<DownloaderBuilderPart>,
 }
@@ -88,6 +277,12 @@ impl DownloaderBuilder {
                 headers: HeaderMap::new(),
                 client: None,
                 max_download_concurrency: 10,
+                max_retries: 4,
+                live: false,
+                max_duration: None,
+                decrypt: false,
+                variant_selector: VariantSelector::default(),
+                on_progress: None,
             }),
         }
     }
@@ -151,11 +346,88 @@ impl DownloaderBuilder {
         }
     }
 
-    pub async fn download(self) -> anyhow::Result<()> {
-        if self.inner.is_err() {
-            return Err(self.inner.unwrap_err());
+    /// attempts per segment (and key fetch) before giving up, with
+    /// exponential backoff between them. Default 4.
+    pub fn max_retries(self, max: usize) -> Self {
+        match self.inner {
+            Ok(mut part) => {
+                part.max_retries = max;
+                Self { inner: Ok(part) }
+            }
+            Err(e) => Self { inner: Err(e) },
+        }
+    }
+
+    /// record a live/DVR stream: keep polling the playlist for new segments
+    /// instead of stopping after the first snapshot
+    pub fn live(self, live: bool) -> Self {
+        match self.inner {
+            Ok(mut part) => {
+                part.live = live;
+                Self { inner: Ok(part) }
+            }
+            Err(e) => Self { inner: Err(e) },
         }
-        let part = self.inner.unwrap();
+    }
+
+    /// stop live recording once this much time has elapsed, even if the
+    /// playlist never grows an `EXT-X-ENDLIST`
+    pub fn max_duration(self, max: std::time::Duration) -> Self {
+        match self.inner {
+            Ok(mut part) => {
+                part.max_duration = Some(max);
+                Self { inner: Ok(part) }
+            }
+            Err(e) => Self { inner: Err(e) },
+        }
+    }
+
+    /// pick which master-playlist variant to download. Defaults to
+    /// `VariantSelector::Highest`.
+    pub fn variant(self, selector: VariantSelector) -> Self {
+        match self.inner {
+            Ok(mut part) => {
+                part.variant_selector = selector;
+                Self { inner: Ok(part) }
+            }
+            Err(e) => Self { inner: Err(e) },
+        }
+    }
+
+    /// decrypt AES-128 segments in-pipeline instead of leaving the key
+    /// handling to the player/ffmpeg when merging
+    pub fn decrypt(self, decrypt: bool) -> Self {
+        match self.inner {
+            Ok(mut part) => {
+                part.decrypt = decrypt;
+                Self { inner: Ok(part) }
+            }
+            Err(e) => Self { inner: Err(e) },
+        }
+    }
+
+    /// receive a `DownloadEvent` per playlist resolution, key fetch and
+    /// segment completion, instead of the bundled `indicatif` progress bar.
+    /// Useful for rendering a custom UI, updating a web client, or logging
+    /// structured progress.
+    pub fn on_progress<F>(self, callback: F) -> Self
+    where
+        F: Fn(DownloadEvent) + Send + Sync + 'static,
+    {
+        match self.inner {
+            Ok(mut part) => {
+                part.on_progress = Some(Arc::new(callback));
+                Self { inner: Ok(part) }
+            }
+            Err(e) => Self { inner: Err(e) },
+        }
+    }
+
+    pub async fn download(self) -> anyhow::Result<()> {
+        let part = match self.inner {
+            Ok(part) => part,
+            Err(e) => return Err(e),
+        };
         let client = {
             if part.client.is_some() {
                 part.client.unwrap()
@@ -164,6 +436,11 @@ impl DownloaderBuilder {
             }
         };
 
+        let on_progress = part.on_progress.unwrap_or_else(|| {
+            let reporter = Arc::new(DefaultProgressReporter::new());
+            Arc::new(move |event| reporter.handle(event))
+        });
+
         let downloader = Arc::new(Downloader {
             target: part.target,
             save_dir: part.save_dir.unwrap_or(std::path::PathBuf::from(".")),
@@ -171,14 +448,48 @@ impl DownloaderBuilder {
             client: client,
             header: part.headers,
             max_download_concurrency: part.max_download_concurrency,
+            max_retries: part.max_retries,
+            live: part.live,
+            max_duration: part.max_duration,
+            decrypt: part.decrypt,
+            variant_selector: part.variant_selector,
+            on_progress,
         });
 
         tracing::info!("downloader: {:?}", &downloader.target);
         downloader.download().await
     }
+
+    /// fetch the target and describe its available variant streams, without
+    /// downloading anything. Returns an empty list if the target is already
+    /// a media playlist (nothing to choose between).
+    pub async fn list_variants(self) -> anyhow::Result<Vec<String>> {
+        let part = match self.inner {
+            Ok(part) => part,
+            Err(e) => return Err(e),
+        };
+        let client = part.client.unwrap_or_else(reqwest::Client::new);
+
+        let bytes = client
+            .get(part.target.as_str())
+            .headers(part.headers)
+            .send()
+            .await?
+            .bytes()
+            .await?;
+
+        match m3u8_rs::parse_playlist_res(&bytes) {
+            Ok(Playlist::MasterPlaylist(master)) => Ok(master
+                .variants
+                .iter()
+                .map(Downloader::describe_variant)
+                .collect()),
+            Ok(Playlist::MediaPlaylist(_)) => Ok(Vec::new()),
+            Err(e) => Err(anyhow::anyhow!("parse m3u8 error: {}", e)),
+        }
+    }
 }
 
-#[derive(Debug)]
 pub struct Downloader {
     target: url::Url,
     save_dir: std::path::PathBuf,
@@ -187,12 +498,27 @@ pub struct Downloader {
     client: reqwest::Client,
     header: HeaderMap,
     max_download_concurrency: usize,
+    max_retries: usize,
+
+    live: bool,
+    max_duration: Option<std::time::Duration>,
+
+    decrypt: bool,
+
+    variant_selector: VariantSelector,
+
+    on_progress: Arc<ProgressCallback>,
 }
 
 impl Downloader {
-    async fn load_m3u8(&self) -> anyhow::Result<M3U8MediaPlaylist> {
+    /// resolves the target down to a media playlist, following a master
+    /// playlist redirect if necessary. Returns the media playlist plus the
+    /// URI of its `EXT-X-MEDIA` audio rendition, if the chosen variant
+    /// references one.
+    async fn load_m3u8(&self) -> anyhow::Result<(M3U8MediaPlaylist, Option<url::Url>)> {
         let mut uri = self.target.clone();
         let mut result = Option::<(Playlist, Bytes)>::None;
+        let mut audio_uri = Option::<url::Url>::None;
 
         loop {
             match result.take() {
@@ -209,29 +535,30 @@ impl Downloader {
                 }
                 Some((Playlist::MasterPlaylist(master), _)) => {
                     tracing::info!("find master playlist, try to get real stream");
-                    let one = master.variants.iter().max_by(|a, b| {
-                        if a.frame_rate.is_some() && b.frame_rate.is_some() {
-                            //return a.frame_rate.unwrap().cmp(&b.frame_rate.unwrap());
-                        }
-                        if a.resolution.is_some() && b.resolution.is_some() {
-                            return a.resolution.unwrap().cmp(&b.resolution.unwrap());
+                    let variant = Self::select_variant(&master.variants, &self.variant_selector)?;
+
+                    if let Some(ref audio_group) = variant.audio {
+                        audio_uri = master
+                            .alternatives
+                            .iter()
+                            .find(|alt| {
+                                alt.media_type == m3u8_rs::AlternativeMediaType::Audio
+                                    && &alt.group_id == audio_group
+                            })
+                            .and_then(|alt| alt.uri.as_deref())
+                            .and_then(|audio_rel| uri.join(audio_rel).ok());
+                    }
+
+                    match uri.join(&variant.uri) {
+                        Ok(stream_uri) => {
+                            uri = stream_uri;
+                            tracing::info!("master redirect to: {:?}", &uri);
+                            continue;
                         }
-                        return a.bandwidth.cmp(&b.bandwidth);
-                    });
-
-                    if let Some(stream) = one {
-                        match uri.join(&stream.uri) {
-                            Ok(stream_uri) => {
-                                uri = stream_uri;
-                                tracing::info!("master redirect to: {:?}", &uri);
-                                continue;
-                            }
-                            Err(e) => {
-                                return Err(anyhow::anyhow!("parse m3u8 error: {}", e));
-                            }
+                        Err(e) => {
+                            return Err(anyhow::anyhow!("parse m3u8 error: {}", e));
                         }
                     }
-                    return Err(anyhow::anyhow!("parse m3u8 error: no stream"));
                 }
                 Some((Playlist::MediaPlaylist(media), bytes)) => {
                     let mut hasher = Md5::new();
@@ -240,37 +567,337 @@ impl Downloader {
 
                     let mut media = M3U8MediaPlaylist::new(media, sum);
                     media.set_base_url(uri);
-                    return Ok(media);
+                    return Ok((media, audio_uri));
                 }
             }
         }
     }
 
+    /// fetch a URI that is already known to point at a media (not master)
+    /// playlist, such as an `EXT-X-MEDIA` audio rendition
+    async fn load_media_playlist(&self, uri: url::Url) -> anyhow::Result<M3U8MediaPlaylist> {
+        let bytes = self.get(uri.as_str()).send().await?.bytes().await?;
+        match m3u8_rs::parse_playlist_res(&bytes) {
+            Ok(Playlist::MediaPlaylist(media)) => {
+                let mut hasher = Md5::new();
+                hasher.update(&bytes);
+                let sum = format!("{:x}", hasher.finalize());
+
+                let mut media = M3U8MediaPlaylist::new(media, sum);
+                media.set_base_url(uri);
+                Ok(media)
+            }
+            Ok(Playlist::MasterPlaylist(_)) => Err(anyhow::anyhow!(
+                "expected a media playlist at {}, found a master playlist",
+                uri
+            )),
+            Err(e) => Err(anyhow::anyhow!("parse m3u8 error: {}", e)),
+        }
+    }
+
+    fn select_variant<'a>(
+        variants: &'a [m3u8_rs::VariantStream],
+        selector: &VariantSelector,
+    ) -> anyhow::Result<&'a m3u8_rs::VariantStream> {
+        anyhow::ensure!(!variants.is_empty(), "master playlist has no variants");
+
+        let chosen = match selector {
+            VariantSelector::Highest => variants.iter().max_by(Self::variant_rank),
+            VariantSelector::Lowest => variants.iter().min_by(Self::variant_rank),
+            VariantSelector::ByBandwidth(max) => variants
+                .iter()
+                .filter(|v| v.bandwidth <= *max)
+                .max_by_key(|v| v.bandwidth),
+            VariantSelector::ByResolution(width, height) => variants
+                .iter()
+                .filter(|v| {
+                    v.resolution
+                        .is_some_and(|r| r.width <= *width && r.height <= *height)
+                })
+                .max_by_key(|v| v.resolution.map_or(0, |r| r.width * r.height)),
+        };
+
+        chosen.ok_or_else(|| {
+            let available = variants
+                .iter()
+                .map(Self::describe_variant)
+                .collect::<Vec<_>>()
+                .join(", ");
+            anyhow::anyhow!(
+                "no variant matches {:?}; available variants: {}",
+                selector,
+                available
+            )
+        })
+    }
+
+    fn variant_rank(a: &&m3u8_rs::VariantStream, b: &&m3u8_rs::VariantStream) -> std::cmp::Ordering {
+        if a.resolution.is_some() && b.resolution.is_some() {
+            return a.resolution.unwrap().cmp(&b.resolution.unwrap());
+        }
+        a.bandwidth.cmp(&b.bandwidth)
+    }
+
+    fn describe_variant(v: &m3u8_rs::VariantStream) -> String {
+        match v.resolution {
+            Some(r) => format!("{}x{} ({}bps)", r.width, r.height, v.bandwidth),
+            None => format!("{}bps", v.bandwidth),
+        }
+    }
+
     fn get(&self, url: &str) -> reqwest::RequestBuilder {
         self.client.get(url).headers(self.header.clone())
     }
 
+    /// GET `uri`, making up to `max_retries` attempts in all, with
+    /// exponential backoff between them, on transport errors or a
+    /// non-success status code
+    async fn get_with_retry(&self, uri: &str) -> anyhow::Result<Bytes> {
+        let mut attempt = 1;
+
+        loop {
+            let outcome = match self.get(uri).send().await {
+                Ok(resp) if resp.status().is_success() => {
+                    resp.bytes().await.map_err(anyhow::Error::from)
+                }
+                Ok(resp) => Err(anyhow::anyhow!(
+                    "request to {} failed with status {}",
+                    uri,
+                    resp.status()
+                )),
+                Err(e) => Err(e.into()),
+            };
+
+            match outcome {
+                Ok(bytes) => return Ok(bytes),
+                Err(e) if attempt >= self.max_retries => return Err(e),
+                Err(e) => {
+                    let backoff = Self::retry_backoff(attempt);
+                    tracing::warn!(
+                        "attempt {}/{} for {} failed: {}, retrying in {:?}",
+                        attempt,
+                        self.max_retries,
+                        uri,
+                        e,
+                        backoff
+                    );
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    fn retry_backoff(attempt: usize) -> std::time::Duration {
+        let millis = 200u64.saturating_mul(1u64 << attempt.min(10));
+        std::time::Duration::from_millis(millis.min(10_000))
+    }
+
     async fn download_m3u8_part(&self, uri: &str) -> anyhow::Result<()> {
+        self.download_segment(uri, None).await
+    }
+
+    /// download a single segment into `save_dir`, optionally decrypting it
+    /// (AES-128-CBC key + IV) before it's written to disk
+    async fn download_segment(
+        &self,
+        uri: &str,
+        decrypt: Option<([u8; 16], [u8; 16])>,
+    ) -> anyhow::Result<()> {
+        self.download_part_into(&self.save_dir, uri, decrypt).await
+    }
+
+    async fn download_part_into(
+        &self,
+        save_dir: &std::path::Path,
+        uri: &str,
+        decrypt: Option<([u8; 16], [u8; 16])>,
+    ) -> anyhow::Result<()> {
         let name = basename(uri);
-        let save_path = self.save_dir.as_path().join(name);
+        let save_path = save_dir.join(name);
         if save_path.exists() {
             return Ok(());
         }
 
-        let bytes = self.get(uri).send().await?.bytes().await?;
-        save_bytes(&save_path, &bytes)
+        let bytes = self.get_with_retry(uri).await?;
+
+        match decrypt {
+            Some((key, iv)) => {
+                let plain = decrypt_aes128_cbc(&key, &iv, &bytes)?;
+                save_bytes(&save_path, &plain)
+            }
+            None => save_bytes(&save_path, &bytes),
+        }
+    }
+
+    /// download every segment of `media` into `save_dir`, decrypting
+    /// in-pipeline if enabled, reporting progress through `self.on_progress`
+    async fn download_media_segments(
+        self: Arc<Self>,
+        media: &M3U8MediaPlaylist,
+        save_dir: &std::path::Path,
+    ) -> anyhow::Result<()> {
+        std::fs::create_dir_all(save_dir)?;
+
+        let sgs = media.segments_with_seq();
+
+        let key_cache = if self.decrypt {
+            let keys = self.fetch_decryption_keys(media, &sgs).await?;
+            if !keys.is_empty() {
+                (self.on_progress)(DownloadEvent::KeyDownloaded);
+            }
+            Some(keys)
+        } else {
+            if let Some(key) = media.key() {
+                self.download_part_into(save_dir, &key, None).await?;
+                (self.on_progress)(DownloadEvent::KeyDownloaded);
+            }
+            None
+        };
+
+        self.download_segment_set(media, save_dir, sgs, &key_cache)
+            .await
+    }
+
+    /// concurrently download `segments` (each paired with its absolute
+    /// sequence number) into `save_dir`, decrypting with `key_cache` if set,
+    /// and reporting progress through `self.on_progress`. Shared by the
+    /// snapshot path (the whole playlist, once) and the live path (only the
+    /// segments newer than the last poll, every round), so both drive the
+    /// same `DownloadEvent` stream.
+    async fn download_segment_set(
+        self: Arc<Self>,
+        media: &M3U8MediaPlaylist,
+        save_dir: &std::path::Path,
+        segments: Vec<(u64, String)>,
+        key_cache: &Option<std::collections::HashMap<String, [u8; 16]>>,
+    ) -> anyhow::Result<()> {
+        let total = segments.len();
+        (self.on_progress)(DownloadEvent::PlaylistResolved { segments: total });
+
+        let sem = Arc::new(Semaphore::new(self.max_download_concurrency));
+        let mut set = JoinSet::new();
+
+        for (index, (seq, segment)) in segments.into_iter().enumerate() {
+            let decrypt_info = key_cache
+                .as_ref()
+                .and_then(|keys| self.decrypt_info_for(media, keys, seq));
+
+            set.spawn({
+                let self2 = self.clone();
+                let sem = sem.clone();
+                let save_dir = save_dir.to_path_buf();
+                async move {
+                    let permit = sem.acquire().await;
+                    if permit.is_err() {
+                        return Ok(());
+                    }
+                    match self2
+                        .download_part_into(&save_dir, &segment, decrypt_info)
+                        .await
+                    {
+                        Ok(_) => {
+                            (self2.on_progress)(DownloadEvent::SegmentDownloaded {
+                                index,
+                                total,
+                                uri: segment,
+                            });
+                            Ok(())
+                        }
+                        Err(e) => Err((segment, e)),
+                    }
+                }
+            });
+        }
+
+        while let Some(res) = set.join_next().await {
+            let out = res?;
+            if let Err((uri, e)) = out {
+                (self.on_progress)(DownloadEvent::Failed {
+                    uri,
+                    error: e.to_string(),
+                });
+                sem.close();
+                return Err(e);
+            }
+        }
+
+        (self.on_progress)(DownloadEvent::Finished);
+        Ok(())
+    }
+
+    /// fetch the raw AES-128 key bytes for every distinct key URI referenced
+    /// by `segments`, keyed by key URI
+    async fn fetch_decryption_keys(
+        &self,
+        media: &M3U8MediaPlaylist,
+        segments: &[(u64, String)],
+    ) -> anyhow::Result<std::collections::HashMap<String, [u8; 16]>> {
+        let mut keys = std::collections::HashMap::new();
+
+        for (seq, _) in segments {
+            let Some(info) = media.segment_key(*seq) else {
+                continue;
+            };
+            if !matches!(info.method, m3u8_rs::KeyMethod::AES128) {
+                continue;
+            }
+            if keys.contains_key(&info.uri) {
+                continue;
+            }
+
+            let bytes = self.get_with_retry(&info.uri).await?;
+            anyhow::ensure!(
+                bytes.len() == 16,
+                "AES-128 key at {} is not 16 bytes",
+                info.uri
+            );
+            let mut key = [0u8; 16];
+            key.copy_from_slice(&bytes);
+            keys.insert(info.uri.clone(), key);
+        }
+
+        Ok(keys)
+    }
+
+    /// the `(key, iv)` pair to decrypt the segment at `seq` with, if
+    /// decryption is enabled and its key is AES-128
+    fn decrypt_info_for(
+        &self,
+        media: &M3U8MediaPlaylist,
+        keys: &std::collections::HashMap<String, [u8; 16]>,
+        seq: u64,
+    ) -> Option<([u8; 16], [u8; 16])> {
+        let info = media.segment_key(seq)?;
+        if !matches!(info.method, m3u8_rs::KeyMethod::AES128) {
+            return None;
+        }
+        let key = *keys.get(&info.uri)?;
+        let iv = info.iv.unwrap_or_else(|| iv_from_sequence(seq));
+        Some((key, iv))
     }
 
     /// download m3u8
     pub async fn download(self: Arc<Self>) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.save_dir)?;
+
+        if self.live {
+            return self.download_live().await;
+        }
+
+        self.download_snapshot().await
+    }
+
+    /// download once: snapshot the playlist, pull every segment, write the
+    /// index and stop. Suitable for VOD playlists with `EXT-X-ENDLIST`.
+    async fn download_snapshot(self: Arc<Self>) -> anyhow::Result<()> {
         // load m3u8 full bytes
         // merge m3u8 need three parts
         // 1. m3u8 file self
         // 2. key in m3u8
         // 3. segment in m3u8
 
-        let media = self.load_m3u8().await?;
-        std::fs::create_dir_all(&self.save_dir)?;
+        let (media, audio_uri) = self.load_m3u8().await?;
 
         match cache::DownloadRecord::load(&self.save_dir) {
             Ok(record) if record.m3u8_sum == media.content_sum() => {}
@@ -294,65 +921,195 @@ impl Downloader {
             }
         }
 
-        if let Some(key) = media.key() {
-            self.download_m3u8_part(&key).await?;
-            tracing::info!("key downloaded");
+        self.clone()
+            .download_media_segments(&media, &self.save_dir)
+            .await?;
+
+        if let Some(audio_uri) = audio_uri {
+            tracing::info!(
+                "selected variant references an audio rendition, downloading it alongside the video: {:?}",
+                &audio_uri
+            );
+            let audio_media = self.load_media_playlist(audio_uri).await?;
+            let audio_dir = self.save_dir.join("audio");
+            self.clone()
+                .download_media_segments(&audio_media, &audio_dir)
+                .await?;
+            audio_media.write_to_file(audio_dir.join(&self.index_name), self.decrypt)?;
         }
 
-        let sem = Arc::new(Semaphore::new(self.max_download_concurrency));
+        media.write_to_file(&self.save_dir.join(&self.index_name), self.decrypt)?;
+        tracing::info!("segments downloaded");
 
-        let sgs = media.segments();
-        let pb = ProgressBar::new(sgs.len() as u64);
-        pb.set_style(
-            ProgressStyle::with_template(
-                "{spinner:.green} [{wide_bar:.cyan/blue}] {pos}/{len} ({eta})",
-            )
-            .unwrap()
-            .progress_chars("#>-"),
-        );
+        Ok(())
+    }
 
-        let mut set = JoinSet::new();
+    /// record a live playlist: poll it roughly every `target_duration`
+    /// seconds, downloading only segments newer than the highest media
+    /// sequence number already seen, until `EXT-X-ENDLIST` shows up, the
+    /// configured max duration elapses, or the process is interrupted.
+    ///
+    /// the md5 `content_sum` snapshot cache is intentionally not consulted
+    /// here: a live playlist is expected to change between polls. An
+    /// `EXT-X-MEDIA` audio rendition on the selected variant, if any, is not
+    /// recorded in this mode.
+    async fn download_live(self: Arc<Self>) -> anyhow::Result<()> {
+        let index_path = self.save_dir.join(&self.index_name);
 
-        for segment in sgs.into_iter() {
-            set.spawn({
-                let self2 = self.clone();
-                let sem = sem.clone();
-                let pb = pb.clone();
-                async move {
-                    let permit = sem.acquire().await;
-                    if permit.is_err() {
-                        return Ok(());
-                    }
-                    match self2.download_m3u8_part(&segment).await {
-                        Ok(_) => {
-                            pb.inc(1);
-                            Ok(())
-                        }
-                        Err(e) => {
-                            return Err(e);
-                        }
-                    }
+        let mut highest_seen = cache::LiveRecord::load(&self.save_dir)
+            .ok()
+            .map(|record| record.highest_sequence);
+        let start = std::time::Instant::now();
+
+        loop {
+            let (media, audio_uri) = self.load_m3u8().await?;
+            if audio_uri.is_some() {
+                tracing::warn!(
+                    "live: selected variant references an audio rendition, but audio is not recorded in live mode"
+                );
+            }
+
+            let new_segments: Vec<(u64, String)> = media
+                .segments_with_seq()
+                .into_iter()
+                .filter(|(seq, _)| highest_seen.map_or(true, |highest| *seq > highest))
+                .collect();
+
+            let key_cache = if self.decrypt {
+                let keys = self.fetch_decryption_keys(&media, &new_segments).await?;
+                if !keys.is_empty() {
+                    (self.on_progress)(DownloadEvent::KeyDownloaded);
                 }
-            });
-        }
+                Some(keys)
+            } else {
+                if let Some(key) = media.key() {
+                    self.download_m3u8_part(&key).await?;
+                    (self.on_progress)(DownloadEvent::KeyDownloaded);
+                }
+                None
+            };
 
-        while let Some(res) = set.join_next().await {
-            let out = res?;
-            if let Err(e) = out {
-                pb.abandon();
-                sem.close();
-                return Err(e);
+            if new_segments.is_empty() {
+                tracing::info!("live: no new segments yet");
+            } else {
+                let segment_count = new_segments.len();
+                self.clone()
+                    .download_segment_set(&media, &self.save_dir, new_segments, &key_cache)
+                    .await?;
+
+                let new_highest = media.append_to_file(&index_path, highest_seen, self.decrypt)?;
+                highest_seen = Some(new_highest);
+                cache::LiveRecord::new(new_highest).save(&self.save_dir)?;
+                tracing::info!(
+                    "live: recorded {} new segment(s), highest sequence {}",
+                    segment_count,
+                    new_highest
+                );
             }
-        }
 
-        media.write_to_file(&self.save_dir.join(&self.index_name))?;
-        pb.finish_with_message("downloaded");
-        tracing::info!("segments downloaded");
+            if !media.is_live() {
+                tracing::info!("live: EXT-X-ENDLIST reached, recording complete");
+                break;
+            }
+
+            if let Some(max_duration) = self.max_duration {
+                if start.elapsed() >= max_duration {
+                    tracing::info!("live: max duration elapsed, stopping");
+                    break;
+                }
+            }
+
+            let poll_interval = std::time::Duration::from_secs(media.target_duration().max(1));
+            tokio::time::sleep(poll_interval).await;
+        }
 
         Ok(())
     }
 }
 
+#[cfg(test)]
+mod variant_tests {
+    use super::*;
+
+    fn variant(bandwidth: u64, resolution: Option<(u64, u64)>) -> m3u8_rs::VariantStream {
+        m3u8_rs::VariantStream {
+            uri: "stream.m3u8".to_string(),
+            bandwidth,
+            resolution: resolution.map(|(width, height)| m3u8_rs::Resolution { width, height }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn highest_prefers_the_greatest_resolution() {
+        let variants = vec![
+            variant(1_000_000, Some((640, 360))),
+            variant(3_000_000, Some((1920, 1080))),
+            variant(2_000_000, Some((1280, 720))),
+        ];
+        let chosen = Downloader::select_variant(&variants, &VariantSelector::Highest).unwrap();
+        assert_eq!(chosen.bandwidth, 3_000_000);
+    }
+
+    #[test]
+    fn lowest_prefers_the_least_resolution() {
+        let variants = vec![
+            variant(1_000_000, Some((640, 360))),
+            variant(3_000_000, Some((1920, 1080))),
+        ];
+        let chosen = Downloader::select_variant(&variants, &VariantSelector::Lowest).unwrap();
+        assert_eq!(chosen.bandwidth, 1_000_000);
+    }
+
+    #[test]
+    fn by_bandwidth_picks_the_highest_variant_under_the_cap() {
+        let variants = vec![
+            variant(1_000_000, None),
+            variant(2_000_000, None),
+            variant(3_000_000, None),
+        ];
+        let chosen =
+            Downloader::select_variant(&variants, &VariantSelector::ByBandwidth(2_500_000))
+                .unwrap();
+        assert_eq!(chosen.bandwidth, 2_000_000);
+    }
+
+    #[test]
+    fn by_bandwidth_errors_when_nothing_fits_under_the_cap() {
+        let variants = vec![variant(3_000_000, None)];
+        let err = Downloader::select_variant(&variants, &VariantSelector::ByBandwidth(1_000_000))
+            .unwrap_err();
+        assert!(err.to_string().contains("available variants"));
+    }
+
+    #[test]
+    fn by_resolution_picks_the_highest_variant_that_fits() {
+        let variants = vec![
+            variant(1_000_000, Some((640, 360))),
+            variant(2_000_000, Some((1280, 720))),
+            variant(3_000_000, Some((1920, 1080))),
+        ];
+        let chosen =
+            Downloader::select_variant(&variants, &VariantSelector::ByResolution(1280, 720))
+                .unwrap();
+        assert_eq!(chosen.bandwidth, 2_000_000);
+    }
+
+    #[test]
+    fn errors_on_an_empty_variant_list() {
+        assert!(Downloader::select_variant(&[], &VariantSelector::Highest).is_err());
+    }
+}
+
+/// the `EXT-X-KEY` in effect for one segment, resolved to an absolute key
+/// URI
+#[derive(Debug, Clone)]
+pub struct SegmentKey {
+    pub method: m3u8_rs::KeyMethod,
+    pub uri: String,
+    pub iv: Option<[u8; 16]>,
+}
+
 #[derive(Debug)]
 pub struct M3U8MediaPlaylist {
     base_url: Option<url::Url>,
@@ -408,15 +1165,42 @@ impl M3U8MediaPlaylist {
         None
     }
 
+    /// the `EXT-X-KEY` in effect for the segment with absolute sequence
+    /// number `seq`, if any
+    pub fn segment_key(&self, seq: u64) -> Option<SegmentKey> {
+        let idx = seq.checked_sub(self.media.media_sequence)? as usize;
+        // an `EXT-X-KEY` applies to every following segment until the next
+        // one, so take the closest key at or before this segment
+        let key = self
+            .media
+            .segments
+            .get(..=idx)?
+            .iter()
+            .rev()
+            .find_map(|s| s.key.as_ref())?;
+        let uri = key.uri.as_ref()?;
+        Some(SegmentKey {
+            method: key.method.clone(),
+            uri: self.format_url(uri),
+            iv: key.iv.as_ref().and_then(|iv| parse_iv(iv)),
+        })
+    }
+
     /// 需要对文件中的路径做处理
-    pub fn write_to_file<P>(mut self, path: P) -> anyhow::Result<()>
+    ///
+    /// when `strip_keys` is set (native decryption was applied) the
+    /// `EXT-X-KEY` lines are dropped entirely, since the saved segments are
+    /// already plain TS
+    pub fn write_to_file<P>(mut self, path: P, strip_keys: bool) -> anyhow::Result<()>
     where
         P: AsRef<std::path::Path>,
     {
         let mut file = std::fs::File::create(&path)?;
 
         self.media.segments.iter_mut().for_each(|item| {
-            if let Some(ref mut key) = item.key {
+            if strip_keys {
+                item.key = None;
+            } else if let Some(ref mut key) = item.key {
                 if let Some(ref mut uri) = key.uri {
                     *uri = basename(uri).to_string();
                 }
@@ -429,4 +1213,94 @@ impl M3U8MediaPlaylist {
         file.sync_all()?;
         Ok(())
     }
+
+    /// `true` while the playlist has no `EXT-X-ENDLIST`, i.e. it is still
+    /// expected to grow
+    pub fn is_live(&self) -> bool {
+        !self.media.end_list
+    }
+
+    pub fn media_sequence(&self) -> u64 {
+        self.media.media_sequence
+    }
+
+    pub fn target_duration(&self) -> u64 {
+        self.media.target_duration
+    }
+
+    /// segments paired with their absolute media sequence number
+    /// (`media_sequence + index`)
+    pub fn segments_with_seq(&self) -> Vec<(u64, String)> {
+        let base = self.media.media_sequence;
+        self.media
+            .segments
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (base + i as u64, self.format_url(&s.uri)))
+            .collect()
+    }
+
+    fn empty_template(&self) -> MediaPlaylist {
+        let mut media = self.media.clone();
+        media.segments.clear();
+        media
+    }
+
+    /// append segments newer than `after` (or every segment, if `after` is
+    /// `None`) to an index file, creating it if it doesn't exist yet.
+    /// URIs are rewritten to basenames, same as `write_to_file`; `strip_keys`
+    /// likewise drops `EXT-X-KEY` lines once native decryption is applied.
+    /// Returns the highest sequence number now present in the file.
+    pub fn append_to_file<P>(&self, path: P, after: Option<u64>, strip_keys: bool) -> anyhow::Result<u64>
+    where
+        P: AsRef<std::path::Path>,
+    {
+        let mut existing = match std::fs::read(path.as_ref()) {
+            Ok(bytes) => {
+                m3u8_rs::parse_media_playlist_res(&bytes).unwrap_or_else(|_| self.empty_template())
+            }
+            Err(_) => self.empty_template(),
+        };
+
+        let base = self.media.media_sequence;
+        let mut highest = after.unwrap_or(base.saturating_sub(1));
+
+        for (i, segment) in self.media.segments.iter().enumerate() {
+            let seq = base + i as u64;
+            if after.is_some_and(|after| seq <= after) {
+                continue;
+            }
+
+            let mut segment = segment.clone();
+            if strip_keys {
+                segment.key = None;
+            } else if let Some(ref mut key) = segment.key {
+                if let Some(ref mut uri) = key.uri {
+                    *uri = basename(uri).to_string();
+                }
+            }
+            segment.uri = basename(&segment.uri).to_string();
+            existing.segments.push(segment);
+            highest = seq;
+        }
+
+        existing.target_duration = self.media.target_duration;
+        existing.version = self.media.version;
+        // `EXT-X-MEDIA-SEQUENCE` must match the sequence number of
+        // `existing.segments[0]`, not this poll's fetch window start: once
+        // the live source's window has slid past the first segment ever
+        // appended, `base` no longer points at it.
+        if !existing.segments.is_empty() {
+            existing.media_sequence = highest + 1 - existing.segments.len() as u64;
+        } else {
+            existing.media_sequence = base;
+        }
+        existing.end_list = self.media.end_list;
+
+        let mut file = std::fs::File::create(path.as_ref())?;
+        existing.write_to(&mut file)?;
+        file.sync_all()?;
+
+        Ok(highest)
+    }
 }
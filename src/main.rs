@@ -1,6 +1,6 @@
 use clap::Parser;
 
-use m3u8_download::DownloaderBuilder;
+use m3u8_download::{DownloaderBuilder, VariantSelector};
 
 #[derive(Debug, Parser)]
 pub struct Args {
@@ -25,6 +25,64 @@ pub struct Args {
     /// verbose
     #[arg(short = 'v', long = "verbose", default_value_t = false)]
     verbose: bool,
+
+    /// record a live/DVR stream: keep polling the playlist for new segments
+    /// instead of stopping after the first snapshot
+    #[arg(long = "live", default_value_t = false)]
+    live: bool,
+
+    /// stop live recording after this many seconds, even if the playlist
+    /// never ends
+    #[arg(long = "max-duration")]
+    max_duration: Option<u64>,
+
+    /// decrypt AES-128 segments in-pipeline instead of relying on ffmpeg to
+    /// read the EXT-X-KEY during merge
+    #[arg(long = "decrypt", default_value_t = false)]
+    decrypt: bool,
+
+    /// attempts per segment (and key fetch) before giving up
+    #[arg(long = "max-retries", default_value_t = 4)]
+    max_retries: usize,
+
+    /// which master-playlist variant to download: "highest" (default),
+    /// "lowest", a resolution like "720p", or a bandwidth cap like "<=2000k"
+    #[arg(short = 'q', long = "quality", value_parser = parse_quality)]
+    quality: Option<VariantSelector>,
+
+    /// list the master playlist's available quality variants and exit,
+    /// without downloading anything
+    #[arg(long = "list-qualities", default_value_t = false)]
+    list_qualities: bool,
+}
+
+fn parse_quality(s: &str) -> anyhow::Result<VariantSelector> {
+    if let Some(max) = s.strip_prefix("<=") {
+        return Ok(VariantSelector::ByBandwidth(parse_bandwidth(max)?));
+    }
+
+    if let Some(height) = s.strip_suffix('p') {
+        let height: u64 = height
+            .parse()
+            .map_err(|_| anyhow::anyhow!("invalid --quality value: {s}"))?;
+        return Ok(VariantSelector::ByResolution(u64::MAX, height));
+    }
+
+    match s {
+        "highest" => Ok(VariantSelector::Highest),
+        "lowest" => Ok(VariantSelector::Lowest),
+        _ => Err(anyhow::anyhow!("invalid --quality value: {s}")),
+    }
+}
+
+fn parse_bandwidth(s: &str) -> anyhow::Result<u64> {
+    if let Some(thousands) = s.strip_suffix('k').or_else(|| s.strip_suffix('K')) {
+        return Ok(thousands.parse::<u64>()? * 1_000);
+    }
+    if let Some(millions) = s.strip_suffix('m').or_else(|| s.strip_suffix('M')) {
+        return Ok(millions.parse::<u64>()? * 1_000_000);
+    }
+    Ok(s.parse::<u64>()?)
 }
 
 fn parse_key_val<T, U>(s: &str) -> anyhow::Result<(T, U)>
@@ -57,6 +115,31 @@ async fn main() -> anyhow::Result<()> {
         .iter()
         .fold(downloader, |downloader, (k, v)| downloader.header(k, v));
 
+    if args.list_qualities {
+        let variants = downloader.list_variants().await?;
+        if variants.is_empty() {
+            println!("target is already a media playlist; no variants to list");
+        } else {
+            for variant in variants {
+                println!("{}", variant);
+            }
+        }
+        return Ok(());
+    }
+
+    let downloader = downloader
+        .live(args.live)
+        .decrypt(args.decrypt)
+        .max_retries(args.max_retries);
+    let downloader = match args.max_duration {
+        Some(secs) => downloader.max_duration(std::time::Duration::from_secs(secs)),
+        None => downloader,
+    };
+    let downloader = match args.quality {
+        Some(selector) => downloader.variant(selector),
+        None => downloader,
+    };
+
     downloader.download().await?;
 
     let util = m3u8_download::VideoUtil::from_index(index_path)?;
@@ -1,3 +1,5 @@
+use std::io::Write;
+use std::path::Path;
 use std::process::Command;
 
 #[derive(Debug)]
@@ -32,19 +34,50 @@ impl VideoUtil {
         self.verbos = true;
     }
 
+    /// the separately-downloaded `EXT-X-MEDIA` audio rendition for the
+    /// chosen variant, if `Downloader` found and recorded one
+    fn audio_index(&self) -> Option<std::path::PathBuf> {
+        let audio_index = self.index_dir.join("audio").join(&self.index_file);
+        audio_index.exists().then_some(audio_index)
+    }
+
+    fn ffmpeg_available() -> bool {
+        Command::new("ffmpeg").arg("-version").output().is_ok()
+    }
+
     pub fn merge_to(&self, output: &str) -> anyhow::Result<()> {
+        if !Self::ffmpeg_available() {
+            tracing::warn!("ffmpeg not found, falling back to native TS concatenation");
+            return self.merge_native(output);
+        }
+
         let output_path = std::fs::canonicalize(output)
             .unwrap_or_else(|_| std::env::current_dir().unwrap().join(output));
 
+        let audio_index = self.audio_index();
+
         let mut cmd = Command::new("ffmpeg");
         cmd.current_dir(&self.index_dir)
             .arg("-allowed_extensions")
             .arg("ALL")
             .arg("-i")
-            .arg(&self.index_file)
-            .arg("-codec")
-            .arg("copy")
-            .arg(&output_path);
+            .arg(&self.index_file);
+
+        if audio_index.is_some() {
+            cmd.arg("-allowed_extensions")
+                .arg("ALL")
+                .arg("-i")
+                .arg(Path::new("audio").join(&self.index_file));
+        }
+
+        cmd.arg("-codec").arg("copy");
+
+        if audio_index.is_some() {
+            cmd.arg("-map").arg("0:v:0").arg("-map").arg("1:a:0");
+        }
+
+        cmd.arg(&output_path);
+
         if self.verbos {
             cmd.stdout(std::process::Stdio::inherit())
                 .stderr(std::process::Stdio::inherit());
@@ -58,6 +91,50 @@ impl VideoUtil {
         Ok(())
     }
 
+    /// playlist-ordered byte concatenation of the downloaded segments,
+    /// avoiding the `ffmpeg` dependency entirely. MPEG-TS allows naive
+    /// concatenation of aligned packets, so this works for the common case,
+    /// but can't mux a separate audio rendition or repack fMP4 segments —
+    /// both still require ffmpeg.
+    pub fn merge_native(&self, output: &str) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.audio_index().is_none(),
+            "a separate audio rendition was downloaded; muxing it in requires ffmpeg"
+        );
+
+        let m3u8_file = std::fs::read(self.index_dir.join(&self.index_file))?;
+        let playlist = m3u8_rs::parse_media_playlist_res(&m3u8_file)
+            .map_err(|e| anyhow::anyhow!("parse m3u8 failed {}", e))?;
+
+        for seg in &playlist.segments {
+            anyhow::ensure!(
+                seg.uri.to_ascii_lowercase().ends_with(".ts"),
+                "segment {} is not MPEG-TS; native merge only supports TS, install ffmpeg for this stream",
+                seg.uri
+            );
+            anyhow::ensure!(
+                !matches!(
+                    seg.key.as_ref().map(|key| &key.method),
+                    Some(m3u8_rs::KeyMethod::AES128)
+                ),
+                "segment {} is still AES-128 encrypted on disk; re-run the download with --decrypt before merging without ffmpeg",
+                seg.uri
+            );
+        }
+
+        let output_path = std::fs::canonicalize(output)
+            .unwrap_or_else(|_| std::env::current_dir().unwrap().join(output));
+        let mut out = std::fs::File::create(&output_path)?;
+
+        for seg in &playlist.segments {
+            let bytes = std::fs::read(self.index_dir.join(&seg.uri))?;
+            out.write_all(&bytes)?;
+        }
+        out.sync_all()?;
+
+        Ok(())
+    }
+
     pub fn play(&self) -> anyhow::Result<()> {
         let mut cmd = {
             if std::path::Path::new("/usr/bin/mpv").exists() {
@@ -90,29 +167,40 @@ impl VideoUtil {
         Ok(())
     }
 
-    fn remove(&self, name: &str) -> anyhow::Result<()> {
+    fn remove_in(&self, dir: &Path, name: &str) -> anyhow::Result<()> {
         let mut path = std::path::PathBuf::from(name);
         if !path.is_absolute() {
-            path = self.index_dir.join(name);
+            path = dir.join(name);
         }
         std::fs::remove_file(path.as_path()).map_err(|e| e.into())
     }
 
-    pub fn clean_segment(self) -> anyhow::Result<()> {
-        let m3u8_file = std::fs::read(self.index_dir.join(&self.index_file))?;
+    fn clean_playlist_segments(&self, dir: &Path, index: &Path) -> anyhow::Result<()> {
+        let m3u8_file = std::fs::read(dir.join(index))?;
         let m3u8 = m3u8_rs::parse_media_playlist_res(&m3u8_file)
             .map_err(|e| anyhow::anyhow!("parse m3u8 failed {}", e))?;
 
         for seg in m3u8.segments {
-            self.remove(&seg.uri)?;
+            self.remove_in(dir, &seg.uri)?;
 
             if let Some(key) = seg.key {
                 if let Some(uri) = key.uri {
-                    self.remove(&uri)?;
+                    self.remove_in(dir, &uri)?;
                 }
             }
         }
 
         Ok(())
     }
+
+    pub fn clean_segment(self) -> anyhow::Result<()> {
+        self.clean_playlist_segments(&self.index_dir, &self.index_file)?;
+
+        let audio_dir = self.index_dir.join("audio");
+        if audio_dir.join(&self.index_file).exists() {
+            self.clean_playlist_segments(&audio_dir, &self.index_file)?;
+        }
+
+        Ok(())
+    }
 }